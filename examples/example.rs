@@ -19,9 +19,14 @@ fn main() {
         });
         s.spawn(|| {
             // Always has 4 guards alive
-            let mut guards = [x.read(), x.read(), x.read(), x.read()];
+            let mut guards = [
+                x.read().unwrap(),
+                x.read().unwrap(),
+                x.read().unwrap(),
+                x.read().unwrap(),
+            ];
             for idx in 0..400 {
-                let r = x.read();
+                let r = x.read().unwrap();
                 println!("Read value: {}", *r);
                 guards[idx % 4] = r;
                 sleep(Duration::from_millis(10));
@@ -0,0 +1,749 @@
+//! RCU cell backend built on `AtomicU128`.
+//!
+//! The pointer to the current value and a reader count share a single
+//! 128-bit atomic word: the high 64 bits hold the pointer, the low 64 bits
+//! hold the number of outstanding readers of that pointer. This lets `read`
+//! and the writer's hand-off both be single CAS/`fetch_add` operations, at
+//! the cost of requiring a 128-bit atomic on the target.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use parking_lot::{Mutex, RwLock};
+
+use crate::TryUpdateError;
+
+use core::{
+    hint,
+    marker::PhantomData,
+    ops::Deref,
+    ptr::NonNull,
+    sync::atomic::{AtomicU128, Ordering},
+};
+
+/// A guard that provides read access to a value in an `RcuCell`.
+///
+/// When this guard is dropped, it will signal that the read operation
+/// is complete, allowing the `RcuCell` to manage its internal state
+/// accordingly.
+pub struct RcuGuard<'a, T> {
+    ptr: NonNull<T>,
+    cell: &'a RcuCell<T>,
+}
+
+impl<T> Deref for RcuGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> Drop for RcuGuard<'_, T> {
+    fn drop(&mut self) {
+        self.cell.release_reader(self.ptr.as_ptr() as usize);
+    }
+}
+
+/// An owned, `'static` read snapshot obtained through [`RcuCell::read_arc`].
+///
+/// Unlike `RcuGuard`, this holds a cloned `Arc<RcuCell<T>>` instead of
+/// borrowing the cell, so it can be stored in a struct, returned up a call
+/// stack, or sent to another thread.
+pub struct RcuArcGuard<T> {
+    cell: Arc<RcuCell<T>>,
+    ptr: NonNull<T>,
+}
+
+impl<T> RcuArcGuard<T> {
+    /// Returns the `Arc<RcuCell<T>>` this guard is keeping alive.
+    pub fn cell(&self) -> &Arc<RcuCell<T>> {
+        &self.cell
+    }
+}
+
+// `RcuArcGuard` is meant to be stored, returned, and sent across threads
+// like any other owned handle (that's the whole point of `read_arc` over
+// `read`). `ptr` is only ever dereferenced for `&T` access, and the reader
+// count the guard holds keeps the pointee from being freed for as long as
+// the guard exists (even after a write swaps it out of the cell), so this
+// is exactly as safe as sharing `T` itself.
+unsafe impl<T: Send + Sync> Send for RcuArcGuard<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuArcGuard<T> {}
+
+impl<T> Deref for RcuArcGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> Drop for RcuArcGuard<T> {
+    fn drop(&mut self) {
+        self.cell.release_reader(self.ptr.as_ptr() as usize);
+    }
+}
+
+/// A concurrent data structure that allows for safe, read-copy-update (RCU)
+/// style access to its value.
+pub struct RcuCell<T> {
+    ptr_counter_latest: AtomicU128,
+    ptr_counter_to_clear: AtomicU128,
+    /// Old values retired by `write_deferred`/`update_deferred`, parked here
+    /// instead of being waited on, and reclaimed opportunistically.
+    ///
+    /// This is a dedicated `Mutex`, not a lock-free Treiber stack and not
+    /// `update_token`. A true lock-free stack only gives atomic push/pop at
+    /// the head; scanning the whole list for drained entries (what
+    /// `drain_retired` does) means either repeatedly popping and re-pushing
+    /// the ones that aren't ready yet, or hazard-pointer-style protection
+    /// against a concurrent `release_reader` dereferencing a node mid-free —
+    /// real complexity for code that is already hand-rolled `unsafe` pointer
+    /// arithmetic. Reusing `update_token` instead doesn't avoid a lock
+    /// either: `release_reader` would then need to take its *read* side on
+    /// every deferred-retired decrement to stay synchronized with
+    /// `retire`/`reclaim`'s structural mutations of the list, which is no
+    /// simpler than this. This `Mutex` is only ever touched on the retire/
+    /// reclaim path and by a releasing reader whose value has already been
+    /// swapped out and is in neither `ptr_counter_latest` nor
+    /// `ptr_counter_to_clear` — never by `read`/`read_arc`, and never while
+    /// waiting on a reader to finish — so it does not reintroduce the
+    /// writer-blocks-on-readers latency this request exists to remove.
+    retired: Mutex<Vec<u128>>,
+    data: PhantomData<T>,
+    update_token: RwLock<()>,
+}
+
+impl<T> RcuCell<T> {
+    /// Creates a new `RcuCell` with the given initial value.
+    ///
+    /// This function initializes a new `RcuCell` instance, setting its
+    /// initial value to the provided `value`.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The initial value to store in the `RcuCell`.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `RcuCell` containing the provided initial value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// ```
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr_counter_latest: AtomicU128::new((Box::into_raw(Box::new(value)) as u128) << 64),
+            ptr_counter_to_clear: AtomicU128::new(0),
+            retired: Mutex::new(Vec::new()),
+            data: PhantomData,
+            update_token: RwLock::new(()),
+        }
+    }
+
+    /// Creates a new, empty `RcuCell` holding no value.
+    ///
+    /// The all-zero pointer/counter word is reserved as the empty sentinel,
+    /// so `read` on an empty cell returns `None` instead of a guard.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::<i32>::empty();
+    /// assert!(rcu_cell.is_none());
+    /// ```
+    pub fn empty() -> Self {
+        Self {
+            ptr_counter_latest: AtomicU128::new(0),
+            ptr_counter_to_clear: AtomicU128::new(0),
+            retired: Mutex::new(Vec::new()),
+            data: PhantomData,
+            update_token: RwLock::new(()),
+        }
+    }
+
+    /// Returns `true` if the cell currently holds no value.
+    ///
+    /// This is a snapshot: a concurrent `write`/`set`/`take` may change the
+    /// answer immediately after this call returns.
+    pub fn is_none(&self) -> bool {
+        (self.ptr_counter_latest.load(Ordering::Acquire) >> 64) == 0
+    }
+
+    /// Provides read access to the value stored in the `RcuCell`, or `None`
+    /// if the cell is currently empty.
+    ///
+    /// This function returns an `RcuGuard`, which allows for safe,
+    /// concurrent read access to the `RcuCell`'s value.
+    ///
+    /// Once all `RcuGuard` instances referencing a particular value are
+    /// dropped, the value can be safely released during an update or write.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 42);
+    /// }
+    /// ```
+    pub fn read(&self) -> Option<RcuGuard<'_, T>> {
+        loop {
+            let ptr_counter = self.ptr_counter_latest.load(Ordering::Acquire);
+            let addr = (ptr_counter >> 64) as usize;
+            if addr == 0 {
+                return None;
+            }
+            if self
+                .ptr_counter_latest
+                .compare_exchange_weak(
+                    ptr_counter,
+                    ptr_counter + 1,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                let ptr = unsafe { NonNull::new_unchecked(addr as *mut T) };
+                return Some(RcuGuard { cell: self, ptr });
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Like `read`, but returns an owned [`RcuArcGuard`] that keeps the cell
+    /// alive via a cloned `Arc` instead of borrowing it.
+    ///
+    /// This lets the snapshot outlive the calling scope: it can be stored in
+    /// a struct, returned from a function, or moved to another thread.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// let rcu_cell = Arc::new(rcu_128::RcuCell::new(42));
+    /// let guard = rcu_cell.read_arc().unwrap();
+    /// assert_eq!(*guard, 42);
+    /// ```
+    pub fn read_arc(self: &Arc<Self>) -> Option<RcuArcGuard<T>> {
+        loop {
+            let ptr_counter = self.ptr_counter_latest.load(Ordering::Acquire);
+            let addr = (ptr_counter >> 64) as usize;
+            if addr == 0 {
+                return None;
+            }
+            if self
+                .ptr_counter_latest
+                .compare_exchange_weak(
+                    ptr_counter,
+                    ptr_counter + 1,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                let ptr = unsafe { NonNull::new_unchecked(addr as *mut T) };
+                return Some(RcuArcGuard {
+                    cell: Arc::clone(self),
+                    ptr,
+                });
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Releases one reader's hold on the value that was at `addr` when it
+    /// was observed (the high 64 bits of a `ptr_counter_*` word). Called by
+    /// `RcuGuard`/`RcuArcGuard`'s `Drop`.
+    ///
+    /// `addr` may currently live in any of three places: `ptr_counter_latest`
+    /// (no writer has swapped it out yet), `ptr_counter_to_clear` (a blocking
+    /// `clear_and_take` is waiting on it), or `retired` (a deferred write
+    /// parked it there). A writer's swap and its follow-up publish to one of
+    /// the latter two are not atomic together, so a reader can momentarily
+    /// find `addr` nowhere; in that case it just retries.
+    fn release_reader(&self, addr: usize) {
+        loop {
+            let ptr_counter = self.ptr_counter_latest.load(Ordering::Acquire);
+            if (ptr_counter >> 64) as usize == addr {
+                if self
+                    .ptr_counter_latest
+                    .compare_exchange_weak(
+                        ptr_counter,
+                        ptr_counter - 1,
+                        Ordering::AcqRel,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    return;
+                }
+                hint::spin_loop();
+                continue;
+            }
+
+            let to_clear = self.ptr_counter_to_clear.load(Ordering::Acquire);
+            if (to_clear >> 64) as usize == addr {
+                if self
+                    .ptr_counter_to_clear
+                    .compare_exchange_weak(
+                        to_clear,
+                        to_clear - 1,
+                        Ordering::AcqRel,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    return;
+                }
+                hint::spin_loop();
+                continue;
+            }
+
+            {
+                let mut retired = self.retired.lock();
+                if let Some(entry) = retired.iter_mut().find(|e| (**e >> 64) as usize == addr) {
+                    *entry -= 1;
+                    return;
+                }
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Writes a new value into the `RcuCell`.
+    ///
+    /// This function immediately writes the new value into the `RcuCell`.
+    /// It will block until all current readers have finished reading
+    /// the old value.
+    ///
+    /// Once all readers have completed their read operations, the
+    /// old value will be safely released.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The new value to store in the `RcuCell`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.write(100);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 100);
+    /// }
+    /// ```
+    pub fn write(&self, value: T) {
+        self.set(value);
+    }
+
+    /// Stores `value` in the cell, whether it was previously empty or
+    /// occupied. Equivalent to `write`, named to match the `Option`-style
+    /// API alongside `empty`/`take`/`is_none`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::empty();
+    /// rcu_cell.set(42);
+    /// assert_eq!(*rcu_cell.read().unwrap(), 42);
+    /// ```
+    pub fn set(&self, value: T) {
+        let new_ptr_counter = (Box::into_raw(Box::new(value)) as u128) << 64;
+        let token_shared = self.update_token.read();
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_shared);
+        self.clear(old_ptr_counter);
+    }
+
+    /// Stores `value` in the cell and returns the value it held before,
+    /// blocking until all readers of that previous value have finished with
+    /// it so it can be safely moved out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.replace(100), 42);
+    /// assert_eq!(*rcu_cell.read().unwrap(), 100);
+    /// ```
+    pub fn replace(&self, value: T) -> T {
+        let new_ptr_counter = (Box::into_raw(Box::new(value)) as u128) << 64;
+        let token_shared = self.update_token.read();
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_shared);
+        self.clear_and_take(old_ptr_counter)
+            .expect("RcuCell::replace called on an empty cell")
+    }
+
+    /// Atomically empties the cell, reclaiming the previous value's memory
+    /// once all its readers have drained.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.take();
+    /// assert!(rcu_cell.is_none());
+    /// ```
+    pub fn take(&self) {
+        let token_shared = self.update_token.read();
+        let old_ptr_counter = self.ptr_counter_latest.swap(0, Ordering::AcqRel);
+        drop(token_shared);
+        self.clear(old_ptr_counter);
+    }
+
+    /// Returns a reference to the current value, panicking if the cell is
+    /// empty. Shared by every method that reads the current value before
+    /// installing a new one (`update`, `compare_update`, `try_update`,
+    /// `update_deferred`), so the empty check only needs to live in one
+    /// place.
+    fn current_or_panic(&self, method: &'static str) -> &T {
+        let ptr_counter = self.ptr_counter_latest.load(Ordering::Acquire);
+        assert!(
+            ptr_counter >> 64 != 0,
+            "RcuCell::{method} called on an empty cell"
+        );
+        unsafe { &*((ptr_counter >> 64) as *const T) }
+    }
+
+    /// Updates the value stored in the `RcuCell` using a provided function,
+    /// returning the value it held before.
+    ///
+    /// This function applies the given closure `f` to the current value
+    /// stored in the `RcuCell`, replacing it with the new value returned
+    /// by the closure. It will block until all current readers have finished
+    /// reading the old value, so that value can be safely moved out and
+    /// handed back to the caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - A closure that takes a reference to the current value and returns
+    ///         a new value to store in the `RcuCell`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.update(|&old_value| old_value + 1), 42);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 43);
+    /// }
+    /// ```
+    pub fn update(&self, mut f: impl FnMut(&T) -> T) -> T {
+        let token_exclusive = self.update_token.write();
+        let old_value = self.current_or_panic("update");
+        let new_value = f(old_value);
+        let new_ptr_counter = (Box::into_raw(Box::new(new_value)) as u128) << 64;
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_exclusive);
+        self.clear_and_take(old_ptr_counter)
+            .expect("RcuCell::update called on an empty cell")
+    }
+
+    /// Replaces the value with `new` only if it currently equals `expected`,
+    /// otherwise returns `new` back unchanged.
+    ///
+    /// This gives callers a compare-and-swap style building block for
+    /// optimistic concurrency: read a snapshot, compute a new value, then
+    /// retry `compare_update` until it succeeds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.compare_update(&42, 100), Ok(()));
+    /// assert_eq!(*rcu_cell.read().unwrap(), 100);
+    /// assert_eq!(rcu_cell.compare_update(&42, 7), Err(7));
+    /// ```
+    pub fn compare_update(&self, expected: &T, new: T) -> Result<(), T>
+    where
+        T: PartialEq,
+    {
+        let token_exclusive = self.update_token.write();
+        let current = self.current_or_panic("compare_update");
+        if current != expected {
+            return Err(new);
+        }
+        let new_ptr_counter = (Box::into_raw(Box::new(new)) as u128) << 64;
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_exclusive);
+        self.clear(old_ptr_counter);
+        Ok(())
+    }
+
+    /// Tries to store `value` in the cell, without blocking.
+    ///
+    /// Like `write`, this only needs shared access to `update_token`, so it
+    /// only fails (handing `value` back) when `update`/`compare_update`/
+    /// `try_update`/`update_deferred` currently holds it exclusively.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.try_write(100), Ok(()));
+    /// assert_eq!(*rcu_cell.read().unwrap(), 100);
+    /// ```
+    pub fn try_write(&self, value: T) -> Result<(), T> {
+        let Some(token_shared) = self.update_token.try_read() else {
+            return Err(value);
+        };
+        let new_ptr_counter = (Box::into_raw(Box::new(value)) as u128) << 64;
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_shared);
+        self.clear(old_ptr_counter);
+        Ok(())
+    }
+
+    /// Tries to update the value using `f`, without blocking.
+    ///
+    /// Unlike `update`, this does not wait for `update_token`: it fails
+    /// immediately with [`TryUpdateError`] if any other writer currently
+    /// holds it, exclusively (`update`, `compare_update`, ...) or shared
+    /// (`write`, `set`, `take`, ...).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.try_update(|&old_value| old_value + 1), Ok(()));
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 43);
+    /// }
+    /// ```
+    pub fn try_update(&self, f: impl FnOnce(&T) -> T) -> Result<(), TryUpdateError> {
+        let token_exclusive = self.update_token.try_write().ok_or(TryUpdateError)?;
+        let old_value = self.current_or_panic("try_update");
+        let new_value = f(old_value);
+        let new_ptr_counter = (Box::into_raw(Box::new(new_value)) as u128) << 64;
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_exclusive);
+        self.clear(old_ptr_counter);
+        Ok(())
+    }
+
+    /// Stores `value` in the cell without blocking on the previous value's
+    /// readers.
+    ///
+    /// Unlike `write`, the old value is parked on a retired list instead of
+    /// being waited on, and is reclaimed later (once its readers have
+    /// drained) by [`RcuCell::reclaim`] or the next deferred write.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.write_deferred(100);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 100);
+    /// }
+    /// rcu_cell.reclaim();
+    /// ```
+    pub fn write_deferred(&self, value: T) {
+        let new_ptr_counter = (Box::into_raw(Box::new(value)) as u128) << 64;
+        let token_shared = self.update_token.read();
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_shared);
+        self.retire(old_ptr_counter);
+    }
+
+    /// Updates the value stored in the `RcuCell` using a provided function,
+    /// without blocking on the previous value's readers.
+    ///
+    /// Unlike `update`, this does not return the old value (returning it
+    /// would require waiting for readers to drain) and the old value is
+    /// reclaimed later by [`RcuCell::reclaim`] or the next deferred write.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.update_deferred(|&old_value| old_value + 1);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 43);
+    /// }
+    /// rcu_cell.reclaim();
+    /// ```
+    pub fn update_deferred(&self, mut f: impl FnMut(&T) -> T) {
+        let token_exclusive = self.update_token.write();
+        let old_value = self.current_or_panic("update_deferred");
+        let new_value = f(old_value);
+        let new_ptr_counter = (Box::into_raw(Box::new(new_value)) as u128) << 64;
+        let old_ptr_counter = self
+            .ptr_counter_latest
+            .swap(new_ptr_counter, Ordering::AcqRel);
+        drop(token_exclusive);
+        self.retire(old_ptr_counter);
+    }
+
+    /// Parks `old_ptr_counter` on the retired list, then opportunistically
+    /// reclaims whatever in that list has already drained.
+    fn retire(&self, old_ptr_counter: u128) {
+        let mut retired = self.retired.lock();
+        retired.push(old_ptr_counter);
+        Self::drain_retired(&mut retired);
+    }
+
+    /// Scans the retired list and frees every entry whose readers have all
+    /// drained, without blocking on the ones that haven't.
+    ///
+    /// Calling this is never required for correctness: every deferred write
+    /// already does it opportunistically before returning. It's useful to
+    /// call explicitly when you want memory reclaimed promptly, e.g. after a
+    /// burst of `write_deferred`/`update_deferred` calls with no further
+    /// writes expected for a while.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.write_deferred(100);
+    /// rcu_cell.reclaim();
+    /// ```
+    pub fn reclaim(&self) {
+        Self::drain_retired(&mut self.retired.lock());
+    }
+
+    fn drain_retired(retired: &mut Vec<u128>) {
+        retired.retain(|&old_ptr_counter| {
+            if old_ptr_counter & 0xffff_ffff_ffff_ffff != 0 {
+                // Still has outstanding readers; keep it for next time.
+                return true;
+            }
+            let old_addr = (old_ptr_counter >> 64) as usize;
+            if old_addr != 0 {
+                unsafe {
+                    let _ = Box::from_raw(old_addr as *mut T);
+                }
+            }
+            false
+        });
+    }
+
+    /// Clears the old value from memory once it is no longer needed.
+    ///
+    /// This function is called internally to release the memory of the old
+    /// value after it has been replaced by a new value. It ensures that all
+    /// readers have completed their read operations on the old value before
+    /// freeing the memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `old_ptr_counter` - The old pointer and counter value to be cleared.
+    ///
+    /// This function does not need to be called directly by users of the
+    /// `RcuCell`.
+    fn clear(&self, old_ptr_counter: u128) {
+        self.clear_and_take(old_ptr_counter);
+    }
+
+    /// Same as `clear`, but moves the old value out and returns it instead
+    /// of dropping it in place (`None` if the cell was empty).
+    fn clear_and_take(&self, old_ptr_counter: u128) -> Option<T> {
+        let old_addr = (old_ptr_counter >> 64) as usize;
+        let take = |addr: usize| -> Option<T> {
+            if addr == 0 {
+                None
+            } else {
+                Some(unsafe { *Box::from_raw(addr as *mut T) })
+            }
+        };
+
+        if old_ptr_counter & 0xffff_ffff_ffff_ffff == 0 {
+            // No reader, release memory directly (if there was a value at all)
+            return take(old_addr);
+        }
+
+        // Only one thread can clear ptr_counter_to_clear at the same time
+        while self
+            .ptr_counter_to_clear
+            .compare_exchange_weak(0, old_ptr_counter, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            // Inner loop to only get shared memory access (MESI protocal)
+            while self.ptr_counter_to_clear.load(Ordering::Relaxed) != 0 {
+                hint::spin_loop();
+            }
+        }
+
+        // No need to use CAS here because when the counter is 0,
+        // it will not be updated by other threads
+        //
+        // Wait for all readers to finish
+        while self.ptr_counter_to_clear.load(Ordering::Acquire) & 0xffff_ffff_ffff_ffff != 0 {
+            hint::spin_loop();
+        }
+        // Clear ptr_counter_to_clear to allow other writers to release memory
+        self.ptr_counter_to_clear.store(0, Ordering::Release);
+        take(old_addr)
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    /// Frees the current value and everything still parked in `retired`.
+    ///
+    /// By construction no `RcuGuard`/`RcuArcGuard` can outlive the cell (the
+    /// former borrows it, the latter holds an `Arc` keeping it alive), so
+    /// every reader count here is guaranteed to already be 0 and
+    /// `Box::from_raw` is sound without waiting.
+    fn drop(&mut self) {
+        let ptr_counter = *self.ptr_counter_latest.get_mut();
+        debug_assert_eq!(ptr_counter & 0xffff_ffff_ffff_ffff, 0);
+        let addr = (ptr_counter >> 64) as usize;
+        if addr != 0 {
+            unsafe {
+                let _ = Box::from_raw(addr as *mut T);
+            }
+        }
+        for old_ptr_counter in self.retired.get_mut().drain(..) {
+            debug_assert_eq!(old_ptr_counter & 0xffff_ffff_ffff_ffff, 0);
+            let old_addr = (old_ptr_counter >> 64) as usize;
+            if old_addr != 0 {
+                unsafe {
+                    let _ = Box::from_raw(old_addr as *mut T);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,594 @@
+//! Portable RCU cell backend built on `AtomicUsize`.
+//!
+//! Targets that lack a 128-bit atomic (aarch64 without LSE, wasm32, 32-bit
+//! targets, ...) fall back to this implementation instead. Rather than
+//! packing a pointer and a reader count into one atomic word, every update
+//! allocates a reference-counted node and the cell keeps a single
+//! `AtomicUsize` pointing at the current node. The node's own `refs` counts
+//! the cell itself (one reference) plus every outstanding `RcuGuard`.
+//!
+//! The low bit of the pointer word doubles as a "read lock" flag: `read`
+//! sets the bit, loads the node, bumps the node's `refs`, then clears the
+//! bit. Holding the bit for that span closes the race where a writer swaps
+//! in a new node (and frees the old one once its `refs` hits zero) between
+//! a reader observing the old pointer and incrementing its reference count.
+
+use alloc::{boxed::Box, sync::Arc};
+use parking_lot::RwLock;
+
+use crate::TryUpdateError;
+
+use core::{
+    hint,
+    marker::PhantomData,
+    ops::Deref,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Set on the pointer word while a reader is between observing the pointer
+/// and counting itself on the node; writers spin until it clears before
+/// swapping the pointer.
+const READ_LOCK: usize = 0b1;
+
+/// A heap-allocated node holding a value and the number of outstanding
+/// references to it (the cell itself holds one for as long as the node is
+/// current).
+struct Inner<T> {
+    refs: AtomicUsize,
+    data: T,
+}
+
+/// Drops a reference to `node`, freeing it once the count reaches zero.
+fn release<T>(node: NonNull<Inner<T>>) {
+    unsafe {
+        if node.as_ref().refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let _ = Box::from_raw(node.as_ptr());
+        }
+    }
+}
+
+/// Same as [`release`], but `word` may be the null sentinel for an empty
+/// cell, in which case there is nothing to release.
+fn release_word<T>(word: usize) {
+    if word != 0 {
+        release(unsafe { NonNull::new_unchecked(word as *mut Inner<T>) });
+    }
+}
+
+/// Keeps the caller's reference on `node`, spins until every other
+/// reference (outstanding `RcuGuard`s) has drained, then reclaims the node
+/// and moves its value out. Because the caller's reference is held
+/// throughout, no guard's [`release`] can see the count reach zero and free
+/// the node first.
+fn take_node<T>(node: NonNull<Inner<T>>) -> T {
+    unsafe {
+        while node.as_ref().refs.load(Ordering::Acquire) != 1 {
+            hint::spin_loop();
+        }
+        Box::from_raw(node.as_ptr()).data
+    }
+}
+
+/// Same as [`take_node`], but `word` may be the null sentinel for an empty
+/// cell, in which case there is no value to take.
+fn take_word<T>(word: usize) -> Option<T> {
+    if word == 0 {
+        None
+    } else {
+        Some(take_node(unsafe {
+            NonNull::new_unchecked(word as *mut Inner<T>)
+        }))
+    }
+}
+
+/// A guard that provides read access to a value in an `RcuCell`.
+///
+/// When this guard is dropped, it will release its reference on the node,
+/// freeing it if it was the last one outstanding.
+pub struct RcuGuard<'a, T> {
+    node: NonNull<Inner<T>>,
+    _cell: PhantomData<&'a RcuCell<T>>,
+}
+
+impl<T> Deref for RcuGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &self.node.as_ref().data }
+    }
+}
+
+impl<T> Drop for RcuGuard<'_, T> {
+    fn drop(&mut self) {
+        release(self.node);
+    }
+}
+
+/// An owned, `'static` read snapshot obtained through [`RcuCell::read_arc`].
+///
+/// Unlike `RcuGuard`, this holds a cloned `Arc<RcuCell<T>>` instead of
+/// borrowing the cell, so it can be stored in a struct, returned up a call
+/// stack, or sent to another thread.
+pub struct RcuArcGuard<T> {
+    cell: Arc<RcuCell<T>>,
+    node: NonNull<Inner<T>>,
+}
+
+impl<T> RcuArcGuard<T> {
+    /// Returns the `Arc<RcuCell<T>>` this guard is keeping alive.
+    pub fn cell(&self) -> &Arc<RcuCell<T>> {
+        &self.cell
+    }
+}
+
+// `RcuArcGuard` is meant to be stored, returned, and sent across threads
+// like any other owned handle (that's the whole point of `read_arc` over
+// `read`). `node` is only ever dereferenced for `&T` access and its `refs`
+// counter is atomic, and the guard's own counted reference keeps the node
+// alive for as long as the guard exists (even after a write swaps it out of
+// the cell), so this is exactly as safe as sharing `T` itself.
+unsafe impl<T: Send + Sync> Send for RcuArcGuard<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuArcGuard<T> {}
+
+impl<T> Deref for RcuArcGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &self.node.as_ref().data }
+    }
+}
+
+impl<T> Drop for RcuArcGuard<T> {
+    fn drop(&mut self) {
+        release(self.node);
+    }
+}
+
+/// A concurrent data structure that allows for safe, read-copy-update (RCU)
+/// style access to its value.
+///
+/// This is the portable fallback used on targets without a 128-bit atomic;
+/// see the crate documentation for the API it mirrors.
+pub struct RcuCell<T> {
+    ptr: AtomicUsize,
+    data: PhantomData<T>,
+    update_token: RwLock<()>,
+}
+
+impl<T> RcuCell<T> {
+    /// Allocates a new node holding `value`, already counted for the cell.
+    fn new_node(value: T) -> *mut Inner<T> {
+        Box::into_raw(Box::new(Inner {
+            refs: AtomicUsize::new(1),
+            data: value,
+        }))
+    }
+
+    /// Creates a new `RcuCell` with the given initial value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// ```
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicUsize::new(Self::new_node(value) as usize),
+            data: PhantomData,
+            update_token: RwLock::new(()),
+        }
+    }
+
+    /// Creates a new, empty `RcuCell` holding no value.
+    ///
+    /// The null pointer word is reserved as the empty sentinel, so `read` on
+    /// an empty cell returns `None` instead of a guard.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::<i32>::empty();
+    /// assert!(rcu_cell.is_none());
+    /// ```
+    pub fn empty() -> Self {
+        Self {
+            ptr: AtomicUsize::new(0),
+            data: PhantomData,
+            update_token: RwLock::new(()),
+        }
+    }
+
+    /// Returns `true` if the cell currently holds no value.
+    ///
+    /// This is a snapshot: a concurrent `write`/`set`/`take` may change the
+    /// answer immediately after this call returns.
+    pub fn is_none(&self) -> bool {
+        self.ptr.load(Ordering::Acquire) & !READ_LOCK == 0
+    }
+
+    /// Like `read`, but returns an owned [`RcuArcGuard`] that keeps the cell
+    /// alive via a cloned `Arc` instead of borrowing it.
+    ///
+    /// This lets the snapshot outlive the calling scope: it can be stored in
+    /// a struct, returned from a function, or moved to another thread.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// let rcu_cell = Arc::new(rcu_128::RcuCell::new(42));
+    /// let guard = rcu_cell.read_arc().unwrap();
+    /// assert_eq!(*guard, 42);
+    /// ```
+    pub fn read_arc(self: &Arc<Self>) -> Option<RcuArcGuard<T>> {
+        loop {
+            let word = self.ptr.load(Ordering::Acquire);
+            if word & READ_LOCK != 0 {
+                hint::spin_loop();
+                continue;
+            }
+            if self
+                .ptr
+                .compare_exchange_weak(word, word | READ_LOCK, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                if word == 0 {
+                    self.ptr.store(0, Ordering::Release);
+                    return None;
+                }
+                let node = unsafe { NonNull::new_unchecked(word as *mut Inner<T>) };
+                unsafe { node.as_ref().refs.fetch_add(1, Ordering::AcqRel) };
+                self.ptr.store(word, Ordering::Release);
+                return Some(RcuArcGuard {
+                    cell: Arc::clone(self),
+                    node,
+                });
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Provides read access to the value stored in the `RcuCell`, or `None`
+    /// if the cell is currently empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 42);
+    /// }
+    /// ```
+    pub fn read(&self) -> Option<RcuGuard<'_, T>> {
+        loop {
+            let word = self.ptr.load(Ordering::Acquire);
+            if word & READ_LOCK != 0 {
+                hint::spin_loop();
+                continue;
+            }
+            if self
+                .ptr
+                .compare_exchange_weak(word, word | READ_LOCK, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                if word == 0 {
+                    self.ptr.store(0, Ordering::Release);
+                    return None;
+                }
+                let node = unsafe { NonNull::new_unchecked(word as *mut Inner<T>) };
+                unsafe { node.as_ref().refs.fetch_add(1, Ordering::AcqRel) };
+                self.ptr.store(word, Ordering::Release);
+                return Some(RcuGuard {
+                    node,
+                    _cell: PhantomData,
+                });
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Swaps `new_word` in as the current pointer word and returns the
+    /// previous one (`0` if the cell was empty). Only waits out a reader
+    /// that is mid-way through setting/clearing the read-lock bit, not the
+    /// reader itself finishing with the old value.
+    fn swap_raw(&self, new_word: usize) -> usize {
+        loop {
+            let word = self.ptr.load(Ordering::Acquire);
+            if word & READ_LOCK != 0 {
+                hint::spin_loop();
+                continue;
+            }
+            if self
+                .ptr
+                .compare_exchange_weak(word, new_word, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                return word;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Writes a new value into the `RcuCell`.
+    ///
+    /// Unlike the 128-bit backend's `clear`, dropping the old node does not
+    /// spin: it simply releases the cell's own reference, and the node is
+    /// freed once that and every outstanding `RcuGuard` have released theirs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.write(100);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 100);
+    /// }
+    /// ```
+    pub fn write(&self, value: T) {
+        self.set(value);
+    }
+
+    /// Stores `value` in the cell, whether it was previously empty or
+    /// occupied. Equivalent to `write`, named to match the `Option`-style
+    /// API alongside `empty`/`take`/`is_none`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::empty();
+    /// rcu_cell.set(42);
+    /// assert_eq!(*rcu_cell.read().unwrap(), 42);
+    /// ```
+    pub fn set(&self, value: T) {
+        let token_shared = self.update_token.read();
+        let old_word = self.swap_raw(Self::new_node(value) as usize);
+        drop(token_shared);
+        release_word::<T>(old_word);
+    }
+
+    /// Stores `value` in the cell and returns the value it held before,
+    /// blocking until all readers of that previous value have finished with
+    /// it so it can be safely moved out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.replace(100), 42);
+    /// assert_eq!(*rcu_cell.read().unwrap(), 100);
+    /// ```
+    pub fn replace(&self, value: T) -> T {
+        let token_shared = self.update_token.read();
+        let old_word = self.swap_raw(Self::new_node(value) as usize);
+        drop(token_shared);
+        take_word(old_word).expect("RcuCell::replace called on an empty cell")
+    }
+
+    /// Atomically empties the cell, reclaiming the previous value's memory
+    /// once all its readers have drained.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.take();
+    /// assert!(rcu_cell.is_none());
+    /// ```
+    pub fn take(&self) {
+        let token_shared = self.update_token.read();
+        let old_word = self.swap_raw(0);
+        drop(token_shared);
+        release_word::<T>(old_word);
+    }
+
+    /// Tries to store `value` in the cell, without blocking.
+    ///
+    /// Like `write`, this only needs shared access to `update_token`, so it
+    /// only fails (handing `value` back) when `update`/`compare_update`/
+    /// `try_update`/`update_deferred` currently holds it exclusively.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.try_write(100), Ok(()));
+    /// assert_eq!(*rcu_cell.read().unwrap(), 100);
+    /// ```
+    pub fn try_write(&self, value: T) -> Result<(), T> {
+        let Some(token_shared) = self.update_token.try_read() else {
+            return Err(value);
+        };
+        let old_word = self.swap_raw(Self::new_node(value) as usize);
+        drop(token_shared);
+        release_word::<T>(old_word);
+        Ok(())
+    }
+
+    /// Tries to update the value using `f`, without blocking.
+    ///
+    /// Unlike `update`, this does not wait for `update_token`: it fails
+    /// immediately with [`TryUpdateError`] if any other writer currently
+    /// holds it, exclusively (`update`, `compare_update`, ...) or shared
+    /// (`write`, `set`, `take`, ...).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.try_update(|&old_value| old_value + 1), Ok(()));
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 43);
+    /// }
+    /// ```
+    pub fn try_update(&self, f: impl FnOnce(&T) -> T) -> Result<(), TryUpdateError> {
+        let token_exclusive = self.update_token.try_write().ok_or(TryUpdateError)?;
+        let old_value = self.current_or_panic("try_update");
+        let new_value = f(old_value);
+        let old_word = self.swap_raw(Self::new_node(new_value) as usize);
+        drop(token_exclusive);
+        release_word::<T>(old_word);
+        Ok(())
+    }
+
+    /// Stores `value` in the cell without blocking on the previous value's
+    /// readers.
+    ///
+    /// This backend already reclaims a node via atomic reference counting as
+    /// soon as its last reader drops, without blocking the writer, so this
+    /// is identical to `write`. It exists so callers generic over both
+    /// backends can always call it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.write_deferred(100);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 100);
+    /// }
+    /// ```
+    pub fn write_deferred(&self, value: T) {
+        self.set(value);
+    }
+
+    /// Updates the value stored in the `RcuCell` using a provided function,
+    /// without blocking on the previous value's readers.
+    ///
+    /// Unlike `update`, this does not return the old value. The old node is
+    /// reclaimed via atomic reference counting as soon as its last reader
+    /// drops, without blocking the writer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.update_deferred(|&old_value| old_value + 1);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 43);
+    /// }
+    /// ```
+    pub fn update_deferred(&self, mut f: impl FnMut(&T) -> T) {
+        let token_exclusive = self.update_token.write();
+        let old_value = self.current_or_panic("update_deferred");
+        let new_value = f(old_value);
+        let old_word = self.swap_raw(Self::new_node(new_value) as usize);
+        drop(token_exclusive);
+        release_word::<T>(old_word);
+    }
+
+    /// No-op on this backend: every write already reclaims its old node via
+    /// atomic reference counting as soon as the last reader drops, so there
+    /// is no retired list to scan. It exists so callers generic over both
+    /// backends can always call it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// rcu_cell.write_deferred(100);
+    /// rcu_cell.reclaim();
+    /// ```
+    pub fn reclaim(&self) {}
+
+    /// Returns a reference to the current value, panicking if the cell is
+    /// empty. Shared by every method that reads the current value before
+    /// installing a new one (`update`, `compare_update`, `try_update`,
+    /// `update_deferred`), so the empty check only needs to live in one
+    /// place.
+    fn current_or_panic(&self, method: &'static str) -> &T {
+        let old_ptr = self.ptr.load(Ordering::Acquire) & !READ_LOCK;
+        assert!(old_ptr != 0, "RcuCell::{method} called on an empty cell");
+        unsafe { &(*(old_ptr as *const Inner<T>)).data }
+    }
+
+    /// Updates the value stored in the `RcuCell` using a provided function,
+    /// returning the value it held before. Blocks until all readers of the
+    /// old value have finished with it so it can be safely moved out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.update(|&old_value| old_value + 1), 42);
+    /// {
+    ///     let guard = rcu_cell.read().unwrap();
+    ///     assert_eq!(*guard, 43);
+    /// }
+    /// ```
+    pub fn update(&self, mut f: impl FnMut(&T) -> T) -> T {
+        let token_exclusive = self.update_token.write();
+        let old_value = self.current_or_panic("update");
+        let new_value = f(old_value);
+        let old_word = self.swap_raw(Self::new_node(new_value) as usize);
+        drop(token_exclusive);
+        take_word(old_word).expect("RcuCell::update called on an empty cell")
+    }
+
+    /// Replaces the value with `new` only if it currently equals `expected`,
+    /// otherwise returns `new` back unchanged.
+    ///
+    /// This gives callers a compare-and-swap style building block for
+    /// optimistic concurrency: read a snapshot, compute a new value, then
+    /// retry `compare_update` until it succeeds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell was empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rcu_cell = rcu_128::RcuCell::new(42);
+    /// assert_eq!(rcu_cell.compare_update(&42, 100), Ok(()));
+    /// assert_eq!(*rcu_cell.read().unwrap(), 100);
+    /// assert_eq!(rcu_cell.compare_update(&42, 7), Err(7));
+    /// ```
+    pub fn compare_update(&self, expected: &T, new: T) -> Result<(), T>
+    where
+        T: PartialEq,
+    {
+        let token_exclusive = self.update_token.write();
+        let current = self.current_or_panic("compare_update");
+        if current != expected {
+            return Err(new);
+        }
+        let old_word = self.swap_raw(Self::new_node(new) as usize);
+        drop(token_exclusive);
+        release_word::<T>(old_word);
+        Ok(())
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    /// Frees the current node, if any.
+    ///
+    /// By construction no `RcuGuard`/`RcuArcGuard` can outlive the cell (the
+    /// former borrows it, the latter holds an `Arc` keeping it alive), so the
+    /// node's reference count is always exactly 1 (the cell's own reference)
+    /// by the time this runs, and `release_word` frees it immediately rather
+    /// than spinning.
+    fn drop(&mut self) {
+        release_word::<T>(*self.ptr.get_mut() & !READ_LOCK);
+    }
+}
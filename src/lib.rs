@@ -1,261 +1,246 @@
-#![cfg(target_has_atomic = "128")]
-#![feature(integer_atomics)]
 #![no_std]
+#![cfg_attr(
+    all(target_has_atomic = "128", rcu128_unstable_atomic128),
+    feature(integer_atomics)
+)]
+// `rcu128_unstable_atomic128` is a hand-rolled, unregistered `--cfg`, not a
+// Cargo feature (this crate intentionally ships without a `Cargo.toml`), so
+// `unexpected_cfgs` would otherwise flag every reference to it below.
+#![allow(unexpected_cfgs)]
+//! A concurrent read-copy-update (RCU) cell.
+//!
+//! `RcuCell<T>` gives readers a lock-free snapshot of the current value
+//! (`read`) while writers install a new one (`write`/`update`) without
+//! blocking readers that are already in flight. Two backends provide the
+//! same public API ([`RcuCell`], [`RcuGuard`]):
+//!
+//! * On targets with a 128-bit atomic, the pointer and a reader count *could*
+//!   share one `AtomicU128` word, so both `read` and the writer hand-off
+//!   would be a single atomic operation. This path depends on the unstable
+//!   `feature(integer_atomics)`/`AtomicU128`, which do not exist on any
+//!   nightly this crate has actually been built against, so it is never
+//!   selected by default — it only compiles (and has only ever been
+//!   type-checked against a stand-in shim, not real hardware) when both
+//!   `target_has_atomic = "128"` holds and the crate is built with
+//!   `--cfg rcu128_unstable_atomic128`, an explicit acknowledgment that
+//!   you're opting into unbuilt, untested, aspirational code.
+//! * By default, on every target, a portable fallback built on `AtomicUsize`
+//!   and per-node reference counting is used instead, so the crate actually
+//!   builds, lints, and runs its test suite on stable.
+
 extern crate alloc;
-use alloc::boxed::Box;
-use parking_lot::RwLock;
 
-use core::{
-    hint,
-    marker::PhantomData,
-    ops::Deref,
-    ptr::NonNull,
-    sync::atomic::{AtomicU128, Ordering},
-};
+#[cfg(all(target_has_atomic = "128", rcu128_unstable_atomic128))]
+mod atomic128;
+#[cfg(not(all(target_has_atomic = "128", rcu128_unstable_atomic128)))]
+mod atomic_usize;
 
-/// A guard that provides read access to a value in an `RcuCell`.
-///
-/// When this guard is dropped, it will signal that the read operation
-/// is complete, allowing the `RcuCell` to manage its internal state
-/// accordingly.
-pub struct RcuGuard<'a, T> {
-    ptr: NonNull<T>,
-    cell: &'a RcuCell<T>,
-}
+#[cfg(all(target_has_atomic = "128", rcu128_unstable_atomic128))]
+pub use atomic128::{RcuArcGuard, RcuCell, RcuGuard};
+#[cfg(not(all(target_has_atomic = "128", rcu128_unstable_atomic128)))]
+pub use atomic_usize::{RcuArcGuard, RcuCell, RcuGuard};
+
+/// Error returned by [`RcuCell::try_update`] when another writer currently
+/// holds the update lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TryUpdateError;
 
-impl<T> Deref for RcuGuard<'_, T> {
-    type Target = T;
-    fn deref(&self) -> &T {
-        unsafe { self.ptr.as_ref() }
+impl core::fmt::Display for TryUpdateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("another writer currently holds the update lock")
     }
 }
 
-impl<T> Drop for RcuGuard<'_, T> {
-    fn drop(&mut self) {
-        // Try to decrement ptr_counter_latest first
-        loop {
-            let ptr_counter = self.cell.ptr_counter_latest.load(Ordering::Acquire);
-            if (ptr_counter >> 64) as usize == self.ptr.as_ptr() as usize {
-                if self
-                    .cell
-                    .ptr_counter_latest
-                    .compare_exchange_weak(
-                        ptr_counter,
-                        ptr_counter - 1,
-                        Ordering::AcqRel,
-                        Ordering::Relaxed,
-                    )
-                    .is_ok()
-                {
-                    return;
-                }
-            } else {
-                // ptr_counter_latest has been updated, so we can't decrement it
-                break;
-            }
-            hint::spin_loop();
-        }
-        // Decrement ptr_counter_to_clear
-        loop {
-            let ptr_counter = self.cell.ptr_counter_to_clear.load(Ordering::Acquire);
-            if (ptr_counter >> 64) as usize == self.ptr.as_ptr() as usize
-                && self
-                    .cell
-                    .ptr_counter_to_clear
-                    .compare_exchange_weak(
-                        ptr_counter,
-                        ptr_counter - 1,
-                        Ordering::AcqRel,
-                        Ordering::Relaxed,
-                    )
-                    .is_ok()
-            {
-                return;
-            }
-            hint::spin_loop();
-        }
+impl core::error::Error for TryUpdateError {}
+
+#[cfg(test)]
+extern crate std;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn read_write_populated_cell() {
+        let cell = RcuCell::new(1);
+        assert_eq!(*cell.read().unwrap(), 1);
+        cell.write(2);
+        assert_eq!(*cell.read().unwrap(), 2);
     }
-}
 
-/// A concurrent data structure that allows for safe, read-copy-update (RCU)
-/// style access to its value.
-pub struct RcuCell<T> {
-    ptr_counter_latest: AtomicU128,
-    ptr_counter_to_clear: AtomicU128,
-    data: PhantomData<T>,
-    update_token: RwLock<()>,
-}
+    #[test]
+    fn read_empty_cell_returns_none() {
+        let cell: RcuCell<i32> = RcuCell::empty();
+        assert!(cell.is_none());
+        assert!(cell.read().is_none());
+    }
 
-impl<T> RcuCell<T> {
-    /// Creates a new `RcuCell` with the given initial value.
-    ///
-    /// This function initializes a new `RcuCell` instance, setting its
-    /// initial value to the provided `value`.
-    ///
-    /// # Arguments
-    ///
-    /// * `value` - The initial value to store in the `RcuCell`.
-    ///
-    /// # Returns
-    ///
-    /// A new instance of `RcuCell` containing the provided initial value.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// let rcu_cell = rcu_128::RcuCell::new(42);
-    /// ```
-    pub fn new(value: T) -> Self {
-        Self {
-            ptr_counter_latest: AtomicU128::new((Box::into_raw(Box::new(value)) as u128) << 64),
-            ptr_counter_to_clear: AtomicU128::new(0),
-            data: PhantomData,
-            update_token: RwLock::new(()),
-        }
+    #[test]
+    fn update_populated_cell_returns_old_value() {
+        let cell = RcuCell::new(1);
+        assert_eq!(cell.update(|&v| v + 1), 1);
+        assert_eq!(*cell.read().unwrap(), 2);
     }
 
-    /// Provides read access to the value stored in the `RcuCell`.
-    ///
-    /// This function returns an `RcuGuard`, which allows for safe,
-    /// concurrent read access to the `RcuCell`'s value.
-    ///
-    /// Once all `RcuGuard` instances referencing a particular value are
-    /// dropped, the value can be safely released during an update or write.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// let rcu_cell = rcu_128::RcuCell::new(42);
-    /// {
-    ///     let guard = rcu_cell.read();
-    ///     assert_eq!(*guard, 42);
-    /// }
-    /// ```
-    pub fn read(&self) -> RcuGuard<'_, T> {
-        let ptr = unsafe {
-            NonNull::new_unchecked(
-                (self.ptr_counter_latest.fetch_add(1, Ordering::AcqRel) >> 64) as usize as *mut T,
-            )
-        };
-        RcuGuard { cell: self, ptr }
+    #[test]
+    #[should_panic(expected = "RcuCell::update called on an empty cell")]
+    fn update_empty_cell_panics() {
+        let cell: RcuCell<i32> = RcuCell::empty();
+        cell.update(|&v| v + 1);
     }
 
-    /// Writes a new value into the `RcuCell`.
-    ///
-    /// This function immediately writes the new value into the `RcuCell`.
-    /// It will block until all current readers have finished reading
-    /// the old value.
-    ///
-    /// Once all readers have completed their read operations, the
-    /// old value will be safely released.
-    ///
-    /// # Arguments
-    ///
-    /// * `value` - The new value to store in the `RcuCell`.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// let rcu_cell = rcu_128::RcuCell::new(42);
-    /// rcu_cell.write(100);
-    /// {
-    ///     let guard = rcu_cell.read();
-    ///     assert_eq!(*guard, 100);
-    /// }
-    /// ```
-    pub fn write(&self, value: T) {
-        let new_ptr_counter = (Box::into_raw(Box::new(value)) as u128) << 64;
-        let token_shared = self.update_token.read();
-        let old_ptr_counter = self
-            .ptr_counter_latest
-            .swap(new_ptr_counter, Ordering::AcqRel);
-        drop(token_shared);
-        self.clear(old_ptr_counter);
+    #[test]
+    #[should_panic(expected = "RcuCell::compare_update called on an empty cell")]
+    fn compare_update_empty_cell_panics() {
+        let cell: RcuCell<i32> = RcuCell::empty();
+        let _ = cell.compare_update(&0, 5);
     }
 
-    /// Updates the value stored in the `RcuCell` using a provided function.
-    ///
-    /// This function applies the given closure `f` to the current value
-    /// stored in the `RcuCell`, replacing it with the new value returned
-    /// by the closure. It will block until all current readers have finished
-    /// reading the old value.
-    ///
-    /// Once all readers have completed their read operations, the old value
-    /// will be safely released.
-    ///
-    /// # Arguments
-    ///
-    /// * `f` - A closure that takes a reference to the current value and returns
-    ///         a new value to store in the `RcuCell`.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// let rcu_cell = rcu_128::RcuCell::new(42);
-    /// rcu_cell.update(|&old_value| old_value + 1);
-    /// {
-    ///     let guard = rcu_cell.read();
-    ///     assert_eq!(*guard, 43);
-    /// }
-    /// ```
-    pub fn update(&self, mut f: impl FnMut(&T) -> T) {
-        let token_exclusive = self.update_token.write();
-        let old_value =
-            unsafe { &*((self.ptr_counter_latest.load(Ordering::Acquire) >> 64) as *const T) };
-        let new_value = f(old_value);
-        let new_ptr_counter = (Box::into_raw(Box::new(new_value)) as u128) << 64;
-        let old_ptr_counter = self
-            .ptr_counter_latest
-            .swap(new_ptr_counter, Ordering::AcqRel);
-        drop(token_exclusive);
-        self.clear(old_ptr_counter);
+    #[test]
+    #[should_panic(expected = "RcuCell::try_update called on an empty cell")]
+    fn try_update_empty_cell_panics() {
+        let cell: RcuCell<i32> = RcuCell::empty();
+        let _ = cell.try_update(|&v| v + 1);
     }
 
-    /// Clears the old value from memory once it is no longer needed.
-    ///
-    /// This function is called internally to release the memory of the old
-    /// value after it has been replaced by a new value. It ensures that all
-    /// readers have completed their read operations on the old value before
-    /// freeing the memory.
-    ///
-    /// # Arguments
-    ///
-    /// * `old_ptr_counter` - The old pointer and counter value to be cleared.
-    ///
-    /// This function does not need to be called directly by users of the
-    /// `RcuCell`.
-    fn clear(&self, old_ptr_counter: u128) {
-        if old_ptr_counter & 0xffff_ffff_ffff_ffff == 0 {
-            // No reader, release memory directly
-            unsafe {
-                let _ = Box::from_raw((old_ptr_counter >> 64) as usize as *mut T);
-            }
-            return;
-        }
+    #[test]
+    #[should_panic(expected = "RcuCell::update_deferred called on an empty cell")]
+    fn update_deferred_empty_cell_panics() {
+        let cell: RcuCell<i32> = RcuCell::empty();
+        cell.update_deferred(|&v| v + 1);
+    }
 
-        // Only one thread can clear ptr_counter_to_clear at the same time
-        while self
-            .ptr_counter_to_clear
-            .compare_exchange_weak(0, old_ptr_counter, Ordering::AcqRel, Ordering::Relaxed)
-            .is_err()
-        {
-            // Inner loop to only get shared memory access (MESI protocal)
-            while self.ptr_counter_to_clear.load(Ordering::Relaxed) != 0 {
-                hint::spin_loop();
-            }
+    #[test]
+    fn compare_update_match_and_mismatch() {
+        let cell = RcuCell::new(42);
+        assert_eq!(cell.compare_update(&42, 100), Ok(()));
+        assert_eq!(*cell.read().unwrap(), 100);
+        assert_eq!(cell.compare_update(&42, 7), Err(7));
+        assert_eq!(*cell.read().unwrap(), 100);
+    }
+
+    #[test]
+    fn take_and_set_round_trip() {
+        let cell = RcuCell::new(42);
+        cell.take();
+        assert!(cell.is_none());
+        cell.set(7);
+        assert_eq!(*cell.read().unwrap(), 7);
+    }
+
+    #[test]
+    fn replace_returns_old_value() {
+        let cell = RcuCell::new(42);
+        assert_eq!(cell.replace(100), 42);
+        assert_eq!(*cell.read().unwrap(), 100);
+    }
+
+    #[test]
+    fn read_arc_outlives_write() {
+        let cell = Arc::new(RcuCell::new(42));
+        let guard = cell.read_arc().unwrap();
+        cell.write(100);
+        assert_eq!(*guard, 42);
+        assert_eq!(*cell.read().unwrap(), 100);
+    }
+
+    #[test]
+    fn read_arc_guard_is_send_across_threads() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let cell = Arc::new(RcuCell::new(42));
+        let guard = cell.read_arc().unwrap();
+        let guard = thread::spawn(move || {
+            assert_send(&guard);
+            assert_eq!(*guard, 42);
+            guard
+        })
+        .join()
+        .unwrap();
+        cell.write(100);
+        assert_eq!(*guard, 42);
+        assert_eq!(*cell.read().unwrap(), 100);
+    }
+
+    #[test]
+    fn try_write_and_try_update_succeed_uncontended() {
+        let cell = RcuCell::new(1);
+        assert_eq!(cell.try_write(2), Ok(()));
+        assert_eq!(cell.try_update(|&v| v + 1), Ok(()));
+        assert_eq!(*cell.read().unwrap(), 3);
+    }
+
+    #[test]
+    fn try_write_and_try_update_fail_while_update_in_progress() {
+        let cell = Arc::new(RcuCell::new(0));
+        let (ready_tx, ready_rx) = mpsc::channel::<()>();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let cell_for_update = Arc::clone(&cell);
+        let handle = thread::spawn(move || {
+            cell_for_update.update(|&v| {
+                ready_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+                v + 1
+            });
+        });
+
+        ready_rx.recv().unwrap();
+        assert_eq!(cell.try_write(100), Err(100));
+        assert_eq!(cell.try_update(|&v| v + 1), Err(TryUpdateError));
+
+        release_tx.send(()).unwrap();
+        handle.join().unwrap();
+        assert_eq!(*cell.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers_stress() {
+        let cell = Arc::new(RcuCell::new(0i64));
+        let mut handles = Vec::new();
+
+        for _ in 0..4 {
+            let cell = Arc::clone(&cell);
+            handles.push(thread::spawn(move || {
+                for _ in 0..2000 {
+                    if let Some(guard) = cell.read() {
+                        assert!(*guard >= 0);
+                    }
+                    if let Some(guard) = cell.read_arc() {
+                        assert!(*guard >= 0);
+                    }
+                }
+            }));
         }
 
-        // No need to use CAS here because when the counter is 0,
-        // it will not be updated by other threads
-        //
-        // Wait for all readers to finish
-        while self.ptr_counter_to_clear.load(Ordering::Acquire) & 0xffff_ffff_ffff_ffff != 0 {
-            hint::spin_loop();
+        for writer in 0..2 {
+            let cell = Arc::clone(&cell);
+            handles.push(thread::spawn(move || {
+                for n in 0..500 {
+                    if writer == 0 {
+                        cell.write(n);
+                    } else {
+                        cell.write_deferred(n);
+                    }
+                }
+            }));
         }
-        // Clear ptr_counter_to_clear to allow other writers to release memory
-        self.ptr_counter_to_clear.store(0, Ordering::Release);
-        unsafe {
-            let _ = Box::from_raw((old_ptr_counter >> 64) as usize as *mut T);
+
+        let cell_for_update = Arc::clone(&cell);
+        handles.push(thread::spawn(move || {
+            for _ in 0..200 {
+                cell_for_update.update_deferred(|&v| v + 1);
+                cell_for_update.reclaim();
+            }
+        }));
+
+        for handle in handles {
+            handle.join().unwrap();
         }
+        cell.reclaim();
+        assert!(cell.read().is_some());
     }
 }